@@ -8,12 +8,13 @@
 
 // Generate input logs with: GST_DEBUG="GST_TRACER:7" GST_TRACERS=latency\(flags="pipeline+element+reported"\)
 
-use failure::Error;
+use failure::{format_err, Error};
 use gst_log_parser::parse;
 use gstreamer::{ClockTime, DebugLevel};
 use itertools::Itertools;
 use std::collections::HashMap;
 use std::fs::File;
+use std::io::{IsTerminal, Write};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -41,13 +42,283 @@ struct Opt {
         long = "blanket-view",
         about = "Lumps all the elements under their same `type`; if they're the same element will sum all their latencies."
     )]
-    total_element_view: bool,   
+    total_element_view: bool,
+    #[structopt(
+        name = "group-by",
+        long = "group-by",
+        parse(try_from_str = parse_group_by),
+        about = "Grouping strategy to roll up element/pad names under: `instance` (strip trailing numeric suffixes, e.g. queue0/queue1 -> queue), `bin` (leading path component before ':' or '/', e.g. bin0/queue1:src -> bin0), or `custom=<regex>` (first capture group of a user-supplied regex). Passing this flag enables grouping on its own; `--blanket-view` alone also enables it, defaulting to `instance`."
+    )]
+    group_by: Option<GroupBy>,
+    #[structopt(
+        name = "exclude-filter",
+        long = "exclude-filter",
+        about = "Filter that decides which elements to exclude from the output."
+    )]
+    exclude_filter: Option<String>,
+    #[structopt(
+        name = "output",
+        long = "output",
+        parse(from_os_str),
+        about = "Path to write one CSV row per matched tracer record to (two rows, min and max, for element-reported-latency), instead of printing an aggregated report."
+    )]
+    output: Option<PathBuf>,
+    #[structopt(
+        name = "format",
+        long = "format",
+        default_value = "csv",
+        about = "Format used when writing to `--output`. Only `csv` is supported."
+    )]
+    format: String,
+    #[structopt(
+        name = "budget",
+        long = "budget",
+        parse(try_from_str = parse_budget),
+        about = "Highlight, in red, elements whose latency (p99, since percentiles are always tracked) exceeds this budget, e.g. `5ms` or `200us`."
+    )]
+    budget: Option<ClockTime>,
+    #[structopt(
+        name = "top",
+        long = "top",
+        about = "Only print the N worst offenders by descending latency, regardless of the usual name ordering."
+    )]
+    top: Option<usize>,
+    #[structopt(
+        name = "no-color",
+        long = "no-color",
+        about = "Disable ANSI highlighting of over-budget elements, e.g. when stdout is not a TTY."
+    )]
+    no_color: bool,
+}
+
+fn parse_budget(s: &str) -> Result<ClockTime, String> {
+    let re = Regex::new(r"^(\d+(?:\.\d+)?)(ns|us|ms|s)$").unwrap();
+    let caps = re
+        .captures(s)
+        .ok_or_else(|| format!("Invalid duration '{}': expected e.g. '5ms', '200us'", s))?;
+
+    let value: f64 = caps[1]
+        .parse()
+        .map_err(|_| format!("Invalid number in duration '{}'", s))?;
+
+    let nseconds = match &caps[2] {
+        "ns" => value,
+        "us" => value * 1_000.0,
+        "ms" => value * 1_000_000.0,
+        "s" => value * 1_000_000_000.0,
+        _ => unreachable!(),
+    };
+
+    Ok(ClockTime::from_nseconds(nseconds.round() as u64))
+}
+
+// Grouping strategy applied to an entry key before it is accumulated, so
+// users can roll up latency by pipeline bin or an arbitrary naming
+// convention instead of only by element instance.
+#[derive(Debug)]
+enum GroupBy {
+    Instance,
+    Bin,
+    Custom(Regex),
+}
+
+fn parse_group_by(s: &str) -> Result<GroupBy, String> {
+    if s == "instance" {
+        Ok(GroupBy::Instance)
+    } else if s == "bin" {
+        Ok(GroupBy::Bin)
+    } else if s.starts_with("custom=") {
+        let pattern = &s["custom=".len()..];
+        let re = Regex::new(pattern)
+            .map_err(|e| format!("Invalid --group-by regex '{}': {}", pattern, e))?;
+
+        if re.captures_len() < 2 {
+            return Err(format!(
+                "--group-by custom regex '{}' must have a capture group",
+                pattern
+            ));
+        }
+
+        Ok(GroupBy::Custom(re))
+    } else {
+        Err(format!(
+            "Unknown --group-by strategy '{}': expected 'instance', 'bin', or 'custom=<regex>'",
+            s
+        ))
+    }
+}
+
+// Writes one row per matched tracer record (two for an
+// element-reported-latency record: one for its min, one for its max), for
+// post-processing in a plotting script instead of looking at the
+// aggregated stdout report.
+struct CsvWriter {
+    file: File,
+}
+
+impl CsvWriter {
+    fn create(path: &PathBuf) -> Result<Self, Error> {
+        let mut file = File::create(path)?;
+        writeln!(file, "timestamp,kind,element,src,sink,time_ns")?;
+        Ok(Self { file })
+    }
+
+    fn write_row(
+        &mut self,
+        timestamp: ClockTime,
+        kind: &str,
+        element: &str,
+        src: &str,
+        sink: &str,
+        time_ns: u64,
+    ) -> Result<(), Error> {
+        writeln!(
+            self.file,
+            "{},{},{},{},{},{}",
+            timestamp,
+            kind,
+            csv_field(element),
+            csv_field(src),
+            csv_field(sink),
+            time_ns
+        )?;
+        Ok(())
+    }
+}
+
+// Quotes a CSV field per RFC 4180 if it contains a comma, quote, or
+// newline; GStreamer element/pad names are free-form and occasionally
+// contain commas (e.g. user-supplied `name` properties).
+fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// True if `key` should be kept: it matches `include` (or `include` is
+// unset) and does not match `exclude`.
+fn is_allowed(key: &str, include: Option<&Regex>, exclude: Option<&Regex>) -> bool {
+    include.map_or(true, |f| f.is_match(key)) && !exclude.map_or(false, |f| f.is_match(key))
+}
+
+// Streaming quantile estimator using the P^2 (Piecewise-Parabolic) algorithm:
+// tracks a single quantile within a bounded error without storing samples.
+#[derive(Debug, Clone)]
+struct P2Quantile {
+    p: f64,
+    q: [f64; 5],
+    n: [i64; 5],
+    np: [f64; 5],
+    dn: [f64; 5],
+    count: u64,
+}
+
+impl P2Quantile {
+    fn new(p: f64) -> Self {
+        Self {
+            p,
+            q: [0.0; 5],
+            n: [1, 2, 3, 4, 5],
+            np: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            dn: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, x: f64) {
+        self.count += 1;
+
+        if self.count <= 5 {
+            self.q[(self.count - 1) as usize] = x;
+            if self.count == 5 {
+                self.q.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            }
+            return;
+        }
+
+        if x < self.q[0] {
+            self.q[0] = x;
+        } else if x > self.q[4] {
+            self.q[4] = x;
+        }
+
+        let k = if x < self.q[1] {
+            0
+        } else if x < self.q[2] {
+            1
+        } else if x < self.q[3] {
+            2
+        } else {
+            3
+        };
+
+        for n in self.n.iter_mut().skip(k + 1) {
+            *n += 1;
+        }
+        for (np, dn) in self.np.iter_mut().zip(self.dn.iter()) {
+            *np += dn;
+        }
+
+        for i in 1..4 {
+            let d = self.np[i] - self.n[i] as f64;
+
+            if (d >= 1.0 && self.n[i + 1] - self.n[i] > 1)
+                || (d <= -1.0 && self.n[i - 1] - self.n[i] < -1)
+            {
+                let d = if d >= 0.0 { 1 } else { -1 };
+                let qn = self.parabolic(i, d as f64);
+
+                self.q[i] = if self.q[i - 1] < qn && qn < self.q[i + 1] {
+                    qn
+                } else {
+                    self.linear(i, d)
+                };
+                self.n[i] += d;
+            }
+        }
+    }
+
+    fn parabolic(&self, i: usize, d: f64) -> f64 {
+        let n = &self.n;
+        let q = &self.q;
+
+        q[i] + d / (n[i + 1] - n[i - 1]) as f64
+            * (((n[i] - n[i - 1]) as f64 + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i]) as f64
+                + ((n[i + 1] - n[i]) as f64 - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]) as f64)
+    }
+
+    fn linear(&self, i: usize, d: i64) -> f64 {
+        let j = (i as i64 + d) as usize;
+        self.q[i] + d as f64 * (self.q[j] - self.q[i]) / (self.n[j] - self.n[i]) as f64
+    }
+
+    // Value of the tracked quantile so far; falls back to a sorted lookup
+    // while fewer than 5 samples have been observed.
+    fn value(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        if self.count < 5 {
+            let mut seen = self.q[..self.count as usize].to_vec();
+            seen.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let idx = (((seen.len() - 1) as f64) * self.p).round() as usize;
+            return seen[idx];
+        }
+        self.q[2]
+    }
 }
 
 #[derive(Debug)]
 struct Count {
     n: u64,
     total: ClockTime,
+    min: Option<ClockTime>,
+    max: Option<ClockTime>,
+    p50: P2Quantile,
+    p95: P2Quantile,
+    p99: P2Quantile,
 }
 
 impl Count {
@@ -55,12 +326,50 @@ impl Count {
         Self {
             n: 0,
             total: ClockTime::from_nseconds(0),
+            min: None,
+            max: None,
+            p50: P2Quantile::new(0.5),
+            p95: P2Quantile::new(0.95),
+            p99: P2Quantile::new(0.99),
         }
     }
 
+    fn update(&mut self, new_time: u64) {
+        let time = ClockTime::from_nseconds(new_time);
+
+        self.n += 1;
+        self.total += time;
+        self.min = Some(self.min.map_or(time, |min| std::cmp::min(min, time)));
+        self.max = Some(self.max.map_or(time, |max| std::cmp::max(max, time)));
+
+        self.p50.add(new_time as f64);
+        self.p95.add(new_time as f64);
+        self.p99.add(new_time as f64);
+    }
+
     fn mean(&self) -> ClockTime {
         ClockTime::from_nseconds(self.total.nseconds() / self.n)
     }
+
+    fn min(&self) -> ClockTime {
+        self.min.unwrap_or_else(|| ClockTime::from_nseconds(0))
+    }
+
+    fn max(&self) -> ClockTime {
+        self.max.unwrap_or_else(|| ClockTime::from_nseconds(0))
+    }
+
+    fn p50(&self) -> ClockTime {
+        ClockTime::from_nseconds(self.p50.value().round() as u64)
+    }
+
+    fn p95(&self) -> ClockTime {
+        ClockTime::from_nseconds(self.p95.value().round() as u64)
+    }
+
+    fn p99(&self) -> ClockTime {
+        ClockTime::from_nseconds(self.p99.value().round() as u64)
+    }
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -75,54 +384,172 @@ impl Element {
         }
     }
 }
+
+#[derive(Debug)]
+struct ReportedLatency {
+    min: ClockTime,
+    max: ClockTime,
+}
+
+impl ReportedLatency {
+    fn new(min: u64, max: u64) -> Self {
+        Self {
+            min: ClockTime::from_nseconds(min),
+            max: ClockTime::from_nseconds(max),
+        }
+    }
+
+    fn update(&mut self, min: u64, max: u64) {
+        let min = ClockTime::from_nseconds(min);
+        let max = ClockTime::from_nseconds(max);
+
+        if min < self.min {
+            self.min = min;
+        }
+        if max > self.max {
+            self.max = max;
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Latency {
     element_totals: HashMap<Element, Count>,
+    pipeline_totals: HashMap<(String, String), Count>,
+    reported_totals: HashMap<String, ReportedLatency>,
 }
 
 impl Latency {
     fn new() -> Self {
         Self {
             element_totals: HashMap::new(),
+            pipeline_totals: HashMap::new(),
+            reported_totals: HashMap::new(),
         }
     }
 
-    fn insert_or_get_count(&self, element: &Element) -> &mut Count {
-        let count: &mut Count = self.element_totals
-            .entry(*element)
-            .or_insert_with(Count::new);
+    fn update_count(&mut self, element: Element, new_time: u64) -> &mut Count {
+        let count = self.element_totals.entry(element).or_insert_with(Count::new);
+        count.update(new_time);
+        count
+    }
 
+    fn update_pipeline_count(&mut self, src: String, sink: String, new_time: u64) -> &mut Count {
+        let count = self
+            .pipeline_totals
+            .entry((src, sink))
+            .or_insert_with(Count::new);
+        count.update(new_time);
         count
     }
-    
-    fn update_count(&self, element: Element, new_time: u64) {
-        let mut count = self.element_totals.get_mut(&element).unwrap();
-        count.n += 1;
-        count.total += ClockTime::from_nseconds(new_time);
+
+    fn update_reported(&mut self, element: String, min: u64, max: u64) {
+        self.reported_totals
+            .entry(element)
+            .and_modify(|reported| reported.update(min, max))
+            .or_insert_with(|| ReportedLatency::new(min, max));
     }
-    
+
     /* Replaces `"gesvideourisource0-videoconvertscale"` as "gesvideourisource-videoconvertscale"
     And `"gesvideourisource0"` as "gesvideourisource" */
     fn normalize_name(&self, name: &str) -> String {
         let parts: Vec<&str> = name.split('-').collect();
-        let re = Regex::new(r"\d+$").unwrap(); 
+        let re = Regex::new(r"\d+$").unwrap();
 
-        let first_part = re.replace(parts[0], ""); 
+        let first_part = re.replace(parts[0], "");
 
         if parts.len() > 1 {
             let remaining_parts: Vec<&str> = parts.into_iter().skip(1).collect();
-            format!("{}-{}", first_part, remaining_parts.join("-")) 
+            format!("{}-{}", first_part, remaining_parts.join("-"))
         } else {
             first_part.to_string()
         }
     }
+
+    // Computes the group an entry key rolls up under per the selected
+    // `--group-by` strategy.
+    fn group_name(&self, key: &str, group_by: &GroupBy) -> String {
+        match group_by {
+            GroupBy::Instance => self.normalize_name(key),
+            GroupBy::Bin => {
+                let end = key.find(|c| c == ':' || c == '/').unwrap_or_else(|| key.len());
+                key[..end].to_string()
+            }
+            GroupBy::Custom(re) => re
+                .captures(key)
+                .and_then(|caps| caps.get(1))
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_else(|| key.to_string()),
+        }
+    }
+}
+
+// Orders report entries for display: by descending p99 truncated to the
+// worst `top` offenders when `--top` is set, otherwise alphabetically by
+// label.
+fn order_entries<'a>(
+    mut entries: Vec<(String, &'a Count)>,
+    top: Option<usize>,
+) -> Vec<(String, &'a Count)> {
+    if let Some(top) = top {
+        entries.sort_by(|(_, a), (_, b)| b.p99().cmp(&a.p99()));
+        entries.truncate(top);
+    } else {
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
+    entries
+}
+
+// Prints one report line per `(label, Count)` entry, optionally keeping only
+// the `--top` worst offenders and highlighting those over `--budget` in red.
+fn print_count_report(entries: Vec<(String, &Count)>, opt: &Opt, color: bool) {
+    let entries = order_entries(entries, opt.top);
+
+    for (label, count) in entries {
+        let line = format!(
+            "  {}: mean {} / p50 {} / p95 {} / p99 {} / min {} / max {}",
+            label,
+            count.mean(),
+            count.p50(),
+            count.p95(),
+            count.p99(),
+            count.min(),
+            count.max()
+        );
+
+        let over_budget = opt.budget.map_or(false, |budget| count.p99() > budget);
+        if over_budget && color {
+            println!("\x1b[31m{}\x1b[0m", line);
+        } else {
+            println!("{}", line);
+        }
+    }
 }
 
 fn main() -> Result<(), Error> {
     let opt = Opt::from_args();
-    let input = File::open(opt.input)?;
-    let latency = Latency::new();
+    let input = File::open(&opt.input)?;
+    let mut latency = Latency::new();
     let element_filter: Option<Regex> = opt.element_filter.map(|f| Regex::new(&f).unwrap());
+    let exclude_filter: Option<Regex> = opt.exclude_filter.map(|f| Regex::new(&f).unwrap());
+
+    if opt.output.is_some() && opt.format != "csv" {
+        return Err(format_err!("Unsupported --format '{}', only 'csv' is supported", opt.format));
+    }
+    let mut csv_writer = match opt.output.as_ref() {
+        Some(path) => Some(CsvWriter::create(path)?),
+        None => None,
+    };
+
+    let is_allowed = |key: &str| is_allowed(key, element_filter.as_ref(), exclude_filter.as_ref());
+
+    // `--group-by` is meaningful on its own; `--blanket-view` alone also
+    // enables grouping, defaulting to the `instance` strategy.
+    let group_by = if opt.total_element_view || opt.group_by.is_some() {
+        Some(opt.group_by.unwrap_or(GroupBy::Instance))
+    } else {
+        None
+    };
 
     let mut elt_latency: HashMap<String, Count> = HashMap::new();
     let parsed = parse(input)
@@ -141,34 +568,102 @@ fn main() -> Result<(), Error> {
                     s.get::<String>("src").expect("Missing 'src' field")
                 };
 
-                let entry_key = if opt.total_element_view {
-                    latency.normalize_name(&entry_key)
+                let entry_key = if let Some(group_by) = &group_by {
+                    latency.group_name(&entry_key, group_by)
                 } else {
                     entry_key
                 };
 
-                if let Some(element_filter) = element_filter.as_ref() {
-                    if !element_filter.is_match(&entry_key) {
-                        continue;
-                    }
+                if !is_allowed(&entry_key) {
+                    continue;
+                }
+
+                let time: u64 = s.get("time").expect("Missing 'time' field");
+
+                if let Some(writer) = csv_writer.as_mut() {
+                    writer.write_row(entry.ts, "element-latency", &entry_key, "", "", time)?;
                 }
 
                 let element = Element::new(&entry_key);
-                let count = latency.insert_or_get_count(&element);
+                latency.update_count(element, time);
+            }
+            "latency" => {
+                let src: String = s.get("src").expect("Missing 'src' field");
+                let sink: String = s.get("sink").expect("Missing 'sink' field");
+
+                let src = if let Some(group_by) = &group_by {
+                    latency.group_name(&src, group_by)
+                } else {
+                    src
+                };
+                let sink = if let Some(group_by) = &group_by {
+                    latency.group_name(&sink, group_by)
+                } else {
+                    sink
+                };
+
+                if !is_allowed(&src) && !is_allowed(&sink) {
+                    continue;
+                }
 
                 let time: u64 = s.get("time").expect("Missing 'time' field");
-                latency.update_count(element, time);
+
+                if let Some(writer) = csv_writer.as_mut() {
+                    writer.write_row(entry.ts, "latency", "", &src, &sink, time)?;
+                }
+
+                latency.update_pipeline_count(src, sink, time);
+            }
+            "element-reported-latency" => {
+                let element: String = s.get("element").expect("Missing 'element' field");
+
+                let element = if let Some(group_by) = &group_by {
+                    latency.group_name(&element, group_by)
+                } else {
+                    element
+                };
+
+                if !is_allowed(&element) {
+                    continue;
+                }
+
+                let min: u64 = s.get("min").expect("Missing 'min' field");
+                let max: u64 = s.get("max").expect("Missing 'max' field");
+
+                if let Some(writer) = csv_writer.as_mut() {
+                    writer.write_row(entry.ts, "element-reported-latency-min", &element, "", "", min)?;
+                    writer.write_row(entry.ts, "element-reported-latency-max", &element, "", "", max)?;
+                }
+
+                latency.update_reported(element, min, max);
             }
-            "latency" => { /* TODO */ }
-            "element-reported-latency" => { /* TODO */ }
             _ => {}
         };
     }
 
-    println!("Mean latency:");
-    // Sort by pad name so we can easily compare results
-    for (pad, count) in latency.element_totals.iter().sorted_by(|(a, _), (b, _)| a.name.cmp(&b.name)) {
-        println!("  {}: {}", pad.name, count.mean());
+    if opt.output.is_none() {
+        let color = !opt.no_color && std::io::stdout().is_terminal();
+
+        println!("Element processing latency:");
+        let element_entries = latency
+            .element_totals
+            .iter()
+            .map(|(pad, count)| (pad.name.clone(), count))
+            .collect();
+        print_count_report(element_entries, &opt, color);
+
+        println!("\nReported latency:");
+        for (element, reported) in latency.reported_totals.iter().sorted_by(|(a, _), (b, _)| a.cmp(b)) {
+            println!("  {}: min {} / max {}", element, reported.min, reported.max);
+        }
+
+        println!("\nEnd-to-end pipeline latency:");
+        let pipeline_entries = latency
+            .pipeline_totals
+            .iter()
+            .map(|((src, sink), count)| (format!("{} -> {}", src, sink), count))
+            .collect();
+        print_count_report(pipeline_entries, &opt, color);
     }
 
     Ok(())
@@ -192,4 +687,106 @@ mod tests {
         assert_eq!(latency.normalize_name(sample_element_regular), "queue");
         assert_eq!(latency.normalize_name(sample_element_nvh264enc), "nvh264enc");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn p2_quantile_approximates_median() {
+        let mut p50 = P2Quantile::new(0.5);
+
+        for x in 1..=1001 {
+            p50.add(x as f64);
+        }
+
+        assert!((p50.value() - 501.0).abs() < 10.0);
+    }
+
+    #[test]
+    fn parse_budget_converts_units() {
+        assert_eq!(parse_budget("5ns").unwrap(), ClockTime::from_nseconds(5));
+        assert_eq!(parse_budget("5us").unwrap(), ClockTime::from_nseconds(5_000));
+        assert_eq!(parse_budget("5ms").unwrap(), ClockTime::from_nseconds(5_000_000));
+        assert_eq!(parse_budget("5s").unwrap(), ClockTime::from_nseconds(5_000_000_000));
+        assert_eq!(
+            parse_budget("1.5ms").unwrap(),
+            ClockTime::from_nseconds(1_500_000)
+        );
+    }
+
+    #[test]
+    fn parse_budget_rejects_invalid_input() {
+        assert!(parse_budget("5").is_err());
+        assert!(parse_budget("5minutes").is_err());
+        assert!(parse_budget("ms").is_err());
+    }
+
+    #[test]
+    fn group_name_bin_takes_leading_path_component() {
+        let latency = Latency::new();
+
+        assert_eq!(latency.group_name("bin0/queue1:src", &GroupBy::Bin), "bin0");
+        assert_eq!(latency.group_name("queue1:src", &GroupBy::Bin), "queue1");
+        assert_eq!(latency.group_name("queue1", &GroupBy::Bin), "queue1");
+    }
+
+    #[test]
+    fn group_name_custom_uses_first_capture_group() {
+        let latency = Latency::new();
+        let group_by = parse_group_by("custom=^([a-z]+)\\d*$").unwrap();
+
+        assert_eq!(latency.group_name("queue0", &group_by), "queue");
+        assert_eq!(latency.group_name("UNMATCHED-123", &group_by), "UNMATCHED-123");
+    }
+
+    #[test]
+    fn order_entries_sorts_alphabetically_without_top() {
+        let a = Count::new();
+        let b = Count::new();
+        let entries = vec![("b".to_string(), &b), ("a".to_string(), &a)];
+
+        let ordered = order_entries(entries, None);
+
+        assert_eq!(
+            ordered.into_iter().map(|(label, _)| label).collect::<Vec<_>>(),
+            vec!["a".to_string(), "b".to_string()]
+        );
+    }
+
+    #[test]
+    fn order_entries_keeps_worst_offenders_with_top() {
+        let mut low = Count::new();
+        low.update(10);
+        let mut high = Count::new();
+        high.update(1_000);
+        let mut mid = Count::new();
+        mid.update(100);
+        let entries = vec![
+            ("low".to_string(), &low),
+            ("high".to_string(), &high),
+            ("mid".to_string(), &mid),
+        ];
+
+        let ordered = order_entries(entries, Some(2));
+
+        assert_eq!(
+            ordered.into_iter().map(|(label, _)| label).collect::<Vec<_>>(),
+            vec!["high".to_string(), "mid".to_string()]
+        );
+    }
+
+    #[test]
+    fn csv_field_quotes_commas_and_quotes() {
+        assert_eq!(csv_field("queue0"), "queue0");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("a\"b"), "\"a\"\"b\"");
+    }
+
+    #[test]
+    fn is_allowed_applies_include_and_exclude() {
+        let include = Regex::new("^queue").unwrap();
+        let exclude = Regex::new("bad").unwrap();
+
+        assert!(is_allowed("queue0", Some(&include), Some(&exclude)));
+        assert!(!is_allowed("sink0", Some(&include), Some(&exclude)));
+        assert!(!is_allowed("queuebad", Some(&include), Some(&exclude)));
+        assert!(is_allowed("anything", None, None));
+    }
+}